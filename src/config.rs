@@ -0,0 +1,345 @@
+use std::path::{Path, PathBuf};
+
+use hcs_rs::schema::virtual_machines::Devices;
+use hcs_rs::schema::virtual_machines::resources::{Chipset, ComPort, SerialConsole, Uefi, UefiBootDevice, UefiBootEntry};
+use hcs_rs::schema::virtual_machines::resources::compute::{Memory, Processor, Topology};
+use hcs_rs::schema::virtual_machines::resources::network::NetworkAdapter;
+use hcs_rs::schema::virtual_machines::resources::storage::{Attachment, AttachmentType, Scsi, VirtualSmb, VirtualSmbShare, VirtualSmbShareOptions};
+use hcs_rs::schema::{ComputeSystem, Version, VirtualMachine};
+use serde::Deserialize;
+
+use crate::CLIArgs;
+
+/// Declarative description of a VM, loaded from a `vm.toml` and overlaid with
+/// any CLI flags the user also passed. Mirrors the shape of vore's per-VM
+/// config: a `[global]` section plus repeatable feature blocks.
+#[derive(Debug, Default, Deserialize)]
+pub struct VmConfig {
+    #[serde(default)]
+    pub global: GlobalConfig,
+    #[serde(default)]
+    pub disk: Vec<DiskConfig>,
+    #[serde(default)]
+    pub share: Vec<ShareConfig>,
+    #[serde(default)]
+    pub net: Vec<NetConfig>,
+    #[serde(default)]
+    pub cdrom: Vec<CdromConfig>,
+    /// Sizes in GB of scratch VHDXs to create fresh for this run; see
+    /// [`crate::scratch::ScratchDisk`].
+    #[serde(default)]
+    pub scratch: Vec<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GlobalConfig {
+    pub efi_file: Option<PathBuf>,
+    pub memory: Option<u32>,
+    pub cores: Option<u8>,
+    /// Named secure-boot template id to hand to `Uefi::secure_boot_template_id`.
+    pub secure_boot_template: Option<String>,
+    /// HTTP(S) URL to UEFI HTTP boot from instead of the VmbFs `efi_file`,
+    /// only meaningful alongside a `nat` network adapter.
+    pub http_boot: Option<String>,
+    /// Boot the first `--cdrom`/`[[cdrom]]` entry instead of `efi_file` when
+    /// both are present. Without this, an ISO attached alongside an
+    /// `efi_file` is just extra media and `efi_file` keeps booting; cdrom
+    /// still boots on its own when there's no `efi_file` to fall back to.
+    #[serde(default)]
+    pub boot_cdrom: bool,
+}
+
+/// One `--net` / `[[net]]` virtual NIC. `mode` is one of `nat`, `internal`,
+/// or `external`, the last requiring `switch` to name the Hyper-V switch to
+/// bridge onto.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetConfig {
+    pub mode: String,
+    pub switch: Option<String>,
+    pub mac: Option<String>,
+}
+
+impl NetConfig {
+    /// Resolves the configured mode to the switch name HCS should bind the
+    /// adapter's endpoint to. `nat` and `internal` use the switch Hyper-V
+    /// creates by those names by default; `external` requires the user's own
+    /// switch name.
+    pub fn switch_name(&self) -> Result<&str, String> {
+        match self.mode.as_str() {
+            "nat" => Ok("nat"),
+            "internal" => Ok("internal"),
+            "external" => self.switch.as_deref().ok_or_else(|| "net mode 'external' requires switch=<name>".to_string()),
+            other => Err(format!("unknown net mode '{other}' (expected nat, internal, or external)")),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiskConfig {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CdromConfig {
+    pub path: PathBuf,
+}
+
+/// A host directory exposed to the guest over SMB. `validate()` requires the
+/// path to exist as a directory and not overlap any other mapped share.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShareConfig {
+    pub name: String,
+    pub path: PathBuf,
+    /// Defaults to read-only, matching `--share`'s CLI default, so a
+    /// `[[share]]` can't silently turn writable just by omitting the field.
+    #[serde(default = "default_read_only")]
+    pub read_only: bool,
+    #[serde(default)]
+    pub single_file_mapping: bool,
+    #[serde(default)]
+    pub restrict_file_access: bool,
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+impl VmConfig {
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// CLI flags take priority over whatever the config file said, so a
+    /// `vm.toml` can be used as a base and tweaked per-run without editing it.
+    pub fn overlay_cli(mut self, cliargs: &CLIArgs) -> Self {
+        if let Some(efi_file) = &cliargs.efi_file {
+            self.global.efi_file = Some(efi_file.clone());
+        }
+        if let Some(memory) = cliargs.memory {
+            self.global.memory = Some(memory);
+        }
+        if let Some(cores) = cliargs.cores {
+            self.global.cores = Some(cores);
+        }
+        for disk in &cliargs.disks {
+            self.disk.push(DiskConfig { path: disk.clone() });
+        }
+        self.net.extend(cliargs.net.iter().cloned());
+        for cdrom in &cliargs.cdrom {
+            self.cdrom.push(CdromConfig { path: cdrom.clone() });
+        }
+        if cliargs.boot_cdrom {
+            self.global.boot_cdrom = true;
+        }
+        self.scratch.extend(cliargs.scratch.iter().copied());
+        self.share.extend(cliargs.share.iter().cloned());
+
+        // CLI shares are already canonicalized by main before this point, but
+        // config-file shares aren't; canonicalize all of them here so
+        // validate()'s is_dir/overlap checks see consistent paths regardless
+        // of source.
+        for share in &mut self.share {
+            if let Ok(canon) = dunce::canonicalize(&share.path) {
+                share.path = canon;
+            }
+        }
+
+        // Likewise, main only canonicalizes a CLI-sourced efi_file; a
+        // config-file efi_file reaches validate()/into_compute_system
+        // un-canonicalized otherwise, which into_compute_system then takes
+        // parent()/file_name() of for the VmbFs share path.
+        if let Some(efi_file) = &self.global.efi_file {
+            if let Ok(canon) = dunce::canonicalize(efi_file) {
+                self.global.efi_file = Some(canon);
+            }
+        }
+
+        self
+    }
+
+    /// Checks constraints that can't be expressed in the type system, such
+    /// as `external` net adapters needing a switch name.
+    pub fn validate(&self) -> Result<(), String> {
+        for net in &self.net {
+            net.switch_name()?;
+        }
+        if self.global.efi_file.is_none() && self.cdrom.is_empty() && self.global.http_boot.is_none() {
+            return Err("no boot target: set efi_file, cdrom, or http_boot".to_string());
+        }
+        if let Some(efi_file) = &self.global.efi_file {
+            if !efi_file.is_file() {
+                return Err(format!("efi_file {} is not a file", efi_file.display()));
+            }
+        }
+        if self.global.http_boot.is_some() && !self.net.iter().any(|net| net.mode == "nat") {
+            return Err("http_boot requires a nat net adapter to boot over".to_string());
+        }
+
+        let mut mapped_paths: Vec<&Path> = Vec::new();
+        if let Some(efi_file) = &self.global.efi_file {
+            mapped_paths.push(efi_file.parent().unwrap());
+        }
+        for share in &self.share {
+            if !share.path.is_dir() {
+                return Err(format!("share '{}' path {} is not a directory", share.name, share.path.display()));
+            }
+            for other in &mapped_paths {
+                if share.path.as_path() == *other || share.path.starts_with(other) || other.starts_with(&share.path) {
+                    return Err(format!("share '{}' path {} overlaps another mapped share", share.name, share.path.display()));
+                }
+            }
+            mapped_paths.push(share.path.as_path());
+        }
+
+        Ok(())
+    }
+
+    fn efi_file(&self) -> Option<&Path> {
+        self.global.efi_file.as_deref()
+    }
+
+    /// Builds the `ComputeSystem` that used to be constructed inline in
+    /// `Hypervisor::build`. Boot target, secure-boot template, memory, cores,
+    /// disks, ISOs, shares and NICs come from this config; the schema version,
+    /// the single COM1 port, the default EFI-folder share's options, the SMB
+    /// share flags other than those in `ShareConfig`, and the remaining VM
+    /// settings are fixed here. `endpoint_ids` must have one HNS endpoint GUID per
+    /// entry in `self.net`, in order (see [`crate::hns::Endpoint`]).
+    pub fn into_compute_system(&self, name: &str, com1_pipe: &str, endpoint_ids: &[String]) -> ComputeSystem {
+        let efi_file = self.efi_file();
+
+        // Boot priority: an explicit network target wins, then a CD/DVD
+        // (installer/live media) - but only if there's no efi_file to fall
+        // back to, or the user explicitly opted into cdrom taking priority
+        // via `boot_cdrom` - finally the VmbFs-mapped efi_file that's always
+        // been the default.
+        let boot_cdrom = self.global.boot_cdrom || efi_file.is_none();
+        let boot_this = if let Some(url) = &self.global.http_boot {
+            UefiBootEntry {
+                device_type: UefiBootDevice::NetworkDevice,
+                device_path: url.clone(),
+                disk_number: 0,
+                ..Default::default()
+            }
+        } else if let Some(cdrom) = self.cdrom.first().filter(|_| boot_cdrom) {
+            UefiBootEntry {
+                device_type: UefiBootDevice::VirtualDvd,
+                device_path: cdrom.path.to_string_lossy().to_string(),
+                disk_number: 0,
+                ..Default::default()
+            }
+        } else {
+            let efi_file = efi_file.expect("validate() ensures a boot target is set");
+            UefiBootEntry {
+                device_type: UefiBootDevice::VmbFs,
+                device_path: efi_file.file_name().unwrap().to_string_lossy().to_string(),
+                disk_number: 0,
+                ..Default::default()
+            }
+        };
+
+        let mut shares = vec![];
+        if let Some(efi_file) = efi_file {
+            shares.push(VirtualSmbShare {
+                name: "smb".to_string(),
+                path: efi_file.parent().unwrap().to_string_lossy().to_string(),
+                allowed_files: vec![],
+                options: VirtualSmbShareOptions {
+                    restrict_file_access: false,
+                    single_file_mapping: true,
+                    read_only: true,
+                    pseudo_oplocks: true,
+                    take_backup_privilege: true,
+                    cache_io: true,
+                    share_read: true,
+                    ..Default::default()
+                },
+            });
+        }
+        shares.extend(self.share.iter().map(|share| VirtualSmbShare {
+            name: share.name.clone(),
+            path: share.path.to_string_lossy().to_string(),
+            allowed_files: vec![],
+            options: VirtualSmbShareOptions {
+                restrict_file_access: share.restrict_file_access,
+                single_file_mapping: share.single_file_mapping,
+                read_only: share.read_only,
+                pseudo_oplocks: true,
+                take_backup_privilege: true,
+                cache_io: true,
+                share_read: true,
+                ..Default::default()
+            },
+        }));
+
+        ComputeSystem {
+            owner: name.to_string(),
+            schema_version: Version::schema_version_19h1(),
+            virtual_machine: Some(VirtualMachine {
+                stop_on_reset: true,
+                chipset: Chipset {
+                    uefi: Some(Uefi {
+                        enable_debugger: false,
+                        secure_boot_template_id: self.global.secure_boot_template.clone(),
+                        boot_this: Some(boot_this),
+                        console: SerialConsole::ComPort1,
+                        stop_on_boot_failure: false,
+                    }),
+                    ..Default::default()
+                },
+                compute_topology: Topology {
+                    memory: Memory {
+                        size_in_mb: self.global.memory.unwrap_or(1024) as u64,
+                        ..Default::default()
+                    },
+                    processor: Processor {
+                        count: self.global.cores.unwrap_or(2) as u32,
+                        limit: None,
+                        weight: None,
+                        expose_virtualization_extensions: true,
+                        ..Default::default()
+                    },
+                },
+                devices: Devices {
+                    com_ports: [(0u32, ComPort { named_pipe: com1_pipe.to_string(), optimize_for_debugger: false })].into_iter().collect(),
+                    scsi: self.disk.iter().map(|disk| (AttachmentType::VirtualDisk, disk.path.to_string_lossy().to_string()))
+                        .chain(self.cdrom.iter().map(|cdrom| (AttachmentType::Iso, cdrom.path.to_string_lossy().to_string())))
+                        .enumerate()
+                        .map(|(index, (attachment_type, path))| {
+                            (
+                                format!("disk-{index}"),
+                                Scsi {
+                                    attachments: [
+                                        (0u32, Attachment {
+                                            attachment_type,
+                                            path,
+                                            ..Default::default()
+                                        }),
+                                    ].into_iter().collect(),
+                                }
+                            )
+                        }).collect(),
+                    virtual_smb: Some(VirtualSmb {
+                        shares,
+                        direct_file_mapping_in_mb: 128,
+                    }),
+                    network_adapters: self.net.iter().zip(endpoint_ids).enumerate().map(|(index, (net, endpoint_id))| {
+                        (
+                            format!("net-{index}"),
+                            NetworkAdapter {
+                                endpoint_id: endpoint_id.clone(),
+                                mac_address: net.mac.clone(),
+                                ..Default::default()
+                            }
+                        )
+                    }).collect(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            should_terminate_on_last_handle_closed: true,
+            ..Default::default()
+        }
+    }
+}
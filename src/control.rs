@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+
+use hcs_rs::compute::defs::{HcsOperationHandle, HcsSystemHandle};
+use hcs_rs::computecore::{pause_compute_system, resume_compute_system, save_compute_system, shutdown_compute_system, terminate_compute_system};
+use hcs_rs::HcsResult;
+use serde::{Deserialize, Serialize};
+
+/// A control-plane action that can be dispatched against a live compute
+/// system. Modeled on cloud-hypervisor's move to typed trait-object API
+/// actions instead of one enum carrying unused `Default` fields per variant.
+pub trait ControlAction: Send + Sync {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()>;
+}
+
+pub struct Pause;
+pub struct Resume;
+pub struct Shutdown;
+pub struct Terminate;
+pub struct Save {
+    pub path: PathBuf,
+}
+
+impl ControlAction for Pause {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()> {
+        pause_compute_system(handle, operation, None)
+    }
+}
+
+impl ControlAction for Resume {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()> {
+        resume_compute_system(handle, operation, None)
+    }
+}
+
+impl ControlAction for Shutdown {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()> {
+        shutdown_compute_system(handle, operation, None)
+    }
+}
+
+impl ControlAction for Terminate {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()> {
+        terminate_compute_system(handle, operation, None)
+    }
+}
+
+impl ControlAction for Save {
+    fn invoke(&self, handle: HcsSystemHandle, operation: HcsOperationHandle) -> HcsResult<()> {
+        let body = serde_json::json!({
+            "SaveType": "ToFile",
+            "SaveStateFilePath": self.path.to_string_lossy(),
+        }).to_string();
+        save_compute_system(handle, operation, Some(body.as_str()))
+    }
+}
+
+/// Wire format for a single request over the control pipe. A `ctl`
+/// invocation sends one of these as a JSON line and reads one `CtlResponse`
+/// line back.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CtlRequest {
+    Pause,
+    Resume,
+    Save { path: PathBuf },
+    Shutdown,
+    Terminate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CtlResponse {
+    Ok,
+    Err(String),
+}
+
+pub fn ctl_pipe_name(vm_name: &str) -> String {
+    format!("\\\\.\\pipe\\vm_{vm_name}_ctl")
+}
+
+/// Sends a single request to a running VM's control pipe and waits for its
+/// response. Used by the `efi-runner ctl` invocation.
+pub async fn send_request(vm_name: &str, request: CtlRequest) -> std::io::Result<CtlResponse> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    let client = ClientOptions::new().open(ctl_pipe_name(vm_name))?;
+    let (reader, mut writer) = tokio::io::split(client);
+
+    let line = serde_json::to_string(&request).unwrap() + "\n";
+    writer.write_all(line.as_bytes()).await?;
+
+    let mut response_line = String::new();
+    BufReader::new(reader).read_line(&mut response_line).await?;
+    Ok(serde_json::from_str(response_line.trim())
+        .unwrap_or_else(|e| CtlResponse::Err(format!("bad response: {e}"))))
+}
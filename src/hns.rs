@@ -0,0 +1,23 @@
+use hcs_rs::hns::{create_endpoint, delete_endpoint};
+use hcs_rs::HcsResult;
+
+/// An HNS endpoint created on a given switch for the lifetime of the VM.
+/// `NetworkAdapter.EndpointId` in the compute system schema refers to this
+/// endpoint's GUID, not the switch's name, so one of these has to exist
+/// before the adapter can be wired up. Dropping it deletes the endpoint.
+pub struct Endpoint {
+    pub id: String,
+}
+
+impl Endpoint {
+    pub fn create(switch_name: &str, mac_address: Option<&str>) -> HcsResult<Self> {
+        let id = create_endpoint(switch_name, mac_address)?;
+        Ok(Self { id })
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let _ = delete_endpoint(self.id.as_str());
+    }
+}
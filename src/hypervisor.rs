@@ -1,112 +1,51 @@
 use std::future::Future;
+use std::sync::Arc;
 use std::time::Duration;
 use hcs_rs::compute::defs::{HcsOperationHandle, HcsSystemHandle};
 use hcs_rs::compute::errorcodes::ResultCode;
 use hcs_rs::computecore::{close_operation, create_compute_system, create_operation, get_compute_system_properties, get_operation_result, start_compute_system};
+use hcs_rs::hns::delete_endpoint;
 use hcs_rs::HcsResult;
-use hcs_rs::schema::{ComputeSystem, Version, VirtualMachine};
-use hcs_rs::schema::virtual_machines::Devices;
-use hcs_rs::schema::virtual_machines::resources::{Chipset, ComPort, SerialConsole, Uefi, UefiBootDevice, UefiBootEntry};
-use hcs_rs::schema::virtual_machines::resources::compute::{Memory, Processor, Topology};
-use hcs_rs::schema::virtual_machines::resources::storage::{Attachment, AttachmentType, Scsi, VirtualSmb, VirtualSmbShare, VirtualSmbShareOptions};
 use serde_json::Value;
-use crate::CLIArgs;
+use std::path::PathBuf;
+use crate::config::{DiskConfig, VmConfig};
+use crate::control::{self, ControlAction};
+use crate::hns::Endpoint;
+use crate::monitor::{ExitStatus, Monitor};
+use crate::scratch::ScratchDisk;
 
 #[allow(unused)]
 pub struct Hypervisor {
     name: String,
     unique_id: String,
     handle: HcsSystemHandle,
+    monitor: Monitor,
+    scratch_disks: Vec<ScratchDisk>,
+    endpoints: Vec<Endpoint>,
 }
 
 impl Hypervisor {
-    pub async fn build(name: &str, cliargs: CLIArgs) -> HcsResult<Self> {
+    pub async fn build(name: &str, mut config: VmConfig) -> HcsResult<(Arc<Self>, impl Future<Output=ExitStatus>)> {
         let com1_pipe = format!("\\\\.\\pipe\\vm_{name}_com1");
-        
-        let folder = cliargs.efi_file.parent().unwrap();
-        let efi_file_folder = folder.to_string_lossy().as_ref().to_string();
-        let efi_file_name = cliargs.efi_file.file_name().unwrap()
-            .to_string_lossy().to_string();
-        
-        
-        let compute_config = serde_json::to_string(&ComputeSystem {
-            owner: name.to_string(),
-            schema_version: Version::schema_version_19h1(),
-            virtual_machine: Some(VirtualMachine {
-                stop_on_reset: true,
-                chipset: Chipset {
-                    uefi: Some(Uefi {
-                        enable_debugger: false,
-                        secure_boot_template_id: None,
-                        boot_this: Some(UefiBootEntry {
-                            device_type: UefiBootDevice::VmbFs,
-                            device_path: efi_file_name, // cliargs.efi_file.to_string(),
-                            disk_number: 0,
-                            ..Default::default()
-                        }),
-                        console: SerialConsole::ComPort1,
-                        stop_on_boot_failure: false,
-                    }),
-                    ..Default::default()
-                },
-                compute_topology: Topology {
-                    memory: Memory {
-                        size_in_mb: cliargs.memory as u64,
-                        ..Default::default()
-                    },
-                    processor: Processor {
-                        count: cliargs.cores as u32,
-                        limit: None,
-                        weight: None,
-                        expose_virtualization_extensions: true,
-                        ..Default::default()
-                    },
-                },
-                devices: Devices {
-                    com_ports: [(0u32, ComPort { named_pipe: com1_pipe.clone(), optimize_for_debugger: false }), ].into_iter().collect(),
-                    scsi: cliargs.disks.iter().enumerate().map(|(index, disk)| {
-                        (
-                            format!("disk-{index}"),
-                            Scsi {
-                                attachments: [
-                                    (0u32, Attachment {
-                                        attachment_type: AttachmentType::VirtualDisk,
-                                        path: disk.to_string_lossy().to_string(),
-                                        ..Default::default()
-                                    }),
-                                ].into_iter().collect(),
-                            }
-                        )
-                    }).collect(),
-                    virtual_smb: Some(
-                        VirtualSmb {
-                            shares: vec![VirtualSmbShare {
-                                name: "smb".to_string(),
-                                path: efi_file_folder,
-                                allowed_files: vec![],
-                                options: VirtualSmbShareOptions {
-                                    restrict_file_access    : false,
-                                    single_file_mapping     : true,
-                                    
-                                    read_only               : true,
-                                    pseudo_oplocks          : true,
-                                    take_backup_privilege   : true,
-                                    cache_io                : true,
-                                    share_read              : true,
-                                    ..Default::default()
-                                },
-                            }],
-                            direct_file_mapping_in_mb: 128,
-                        }
-                    ),
-                    ..Default::default()
-                },
-                ..Default::default()
-            }),
-            should_terminate_on_last_handle_closed: true,
-            ..Default::default()
-        }).unwrap();
-        
+
+        // create scratch VHDXs before the config is serialized, so they
+        // exist as real disk attachments by the time HCS parses it
+        let scratch_disks = config.scratch.iter().enumerate()
+            .map(|(index, size_gb)| ScratchDisk::create(*size_gb, index))
+            .collect::<HcsResult<Vec<_>>>()?;
+        for scratch in &scratch_disks {
+            config.disk.push(DiskConfig { path: scratch.path.clone() });
+        }
+
+        // create one HNS endpoint per configured NIC; NetworkAdapter.EndpointId
+        // references one of these by GUID, not the switch name
+        let endpoints = config.net.iter()
+            .map(|net| Endpoint::create(net.switch_name().expect("validated before build"), net.mac.as_deref()))
+            .collect::<HcsResult<Vec<_>>>()?;
+        let endpoint_ids: Vec<String> = endpoints.iter().map(|e| e.id.clone()).collect();
+
+        let compute_config = serde_json::to_string(&config.into_compute_system(name, com1_pipe.as_str(), &endpoint_ids)).unwrap();
+
         // construct the virtual machine
         let operation = async_operation()?;
         let handle = create_compute_system(name, compute_config.as_str(), operation.0, None)?;
@@ -116,7 +55,7 @@ impl Hypervisor {
             }
             code
         })?;
-        
+
         // get the current runtime id
         let operation = async_operation()?;
         get_compute_system_properties(handle, operation.0, Some("{\"PropertyTypes\": [\"GuestConnection\"]}"))?;
@@ -124,6 +63,10 @@ impl Hypervisor {
         let response = serde_json::from_str::<Value>(response.as_str()).unwrap();
         let unique_id = response["RuntimeId"].as_str().unwrap().to_string();
 
+        // watch the system's lifecycle before starting it, so we don't miss
+        // an event that fires early in boot
+        let (monitor, exit) = Monitor::watch(handle)?;
+
         // start the virtual machine
         let operation = async_operation()?;
         start_compute_system(handle, operation.0, None)?;
@@ -139,11 +82,19 @@ impl Hypervisor {
             ResultCode::Unexpected
         })?;
 
-        Ok(Self {
+        let hypervisor = Arc::new(Self {
             name: name.to_string(),
             unique_id,
             handle,
-        })
+            monitor,
+            scratch_disks,
+            endpoints,
+        });
+
+        // let a second `efi-runner ctl` invocation drive this VM
+        Self::spawn_ctl_listener(hypervisor.clone());
+
+        Ok((hypervisor, exit))
     }
 
     async fn proxy_serial(pipe: &str) -> std::io::Result<()> {
@@ -167,6 +118,112 @@ impl Hypervisor {
         });
         Ok(())
     }
+
+    pub async fn pause(&self) -> HcsResult<()> {
+        self.dispatch(&control::Pause).await
+    }
+
+    pub async fn resume(&self) -> HcsResult<()> {
+        self.dispatch(&control::Resume).await
+    }
+
+    pub async fn save(&self, path: PathBuf) -> HcsResult<()> {
+        self.dispatch(&control::Save { path }).await
+    }
+
+    pub async fn shutdown(&self) -> HcsResult<()> {
+        self.dispatch(&control::Shutdown).await
+    }
+
+    pub async fn terminate(&self) -> HcsResult<()> {
+        self.dispatch(&control::Terminate).await
+    }
+
+    async fn dispatch(&self, action: &dyn ControlAction) -> HcsResult<()> {
+        dispatch_action(self.handle, action).await
+    }
+
+    /// Removes this run's scratch VHDXs by path. `main` calls this before
+    /// `std::process::exit`, which skips `ScratchDisk`'s `Drop` impl, so the
+    /// files can't be left to clean themselves up on the way out.
+    pub fn cleanup_scratch_disks(&self) {
+        for scratch in &self.scratch_disks {
+            let _ = std::fs::remove_file(&scratch.path);
+        }
+    }
+
+    /// Deletes this run's HNS endpoints by id, for the same reason
+    /// `cleanup_scratch_disks` exists: `Endpoint`'s `Drop` impl never runs,
+    /// since `main` always exits via `std::process::exit` and the ctl
+    /// listener task holds its own `Arc<Hypervisor>` clone regardless.
+    pub fn cleanup_endpoints(&self) {
+        for endpoint in &self.endpoints {
+            let _ = delete_endpoint(endpoint.id.as_str());
+        }
+    }
+
+    /// Listens on a named pipe (reusing the same plumbing `proxy_serial` uses
+    /// for the COM1 pipe) so a second `efi-runner ctl` invocation can pause,
+    /// resume, save or stop this VM without this process having to expose
+    /// anything beyond its `build` future.
+    fn spawn_ctl_listener(hypervisor: Arc<Self>) {
+        let pipe_name = control::ctl_pipe_name(hypervisor.name.as_str());
+        tokio::spawn(async move {
+            if let Err(e) = serve_ctl(pipe_name.as_str(), hypervisor).await {
+                eprintln!("ctl listener error: {e}");
+            }
+        });
+    }
+}
+
+async fn serve_ctl(pipe_name: &str, hypervisor: Arc<Hypervisor>) -> std::io::Result<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    loop {
+        let server = ServerOptions::new().create(pipe_name)?;
+        server.connect().await?;
+
+        let hypervisor = hypervisor.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = tokio::io::split(server);
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let response = match serde_json::from_str::<control::CtlRequest>(line.as_str()) {
+                    Ok(request) => {
+                        let result = match request {
+                            control::CtlRequest::Pause => hypervisor.pause().await,
+                            control::CtlRequest::Resume => hypervisor.resume().await,
+                            control::CtlRequest::Save { path } => hypervisor.save(path).await,
+                            control::CtlRequest::Shutdown => hypervisor.shutdown().await,
+                            control::CtlRequest::Terminate => hypervisor.terminate().await,
+                        };
+                        match result {
+                            Ok(()) => control::CtlResponse::Ok,
+                            Err(e) => control::CtlResponse::Err(format!("{e:?}")),
+                        }
+                    }
+                    Err(e) => control::CtlResponse::Err(format!("bad request: {e}")),
+                };
+                let line = serde_json::to_string(&response).unwrap() + "\n";
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+async fn dispatch_action(handle: HcsSystemHandle, action: &dyn ControlAction) -> HcsResult<()> {
+    let operation = async_operation()?;
+    action.invoke(handle, operation.0)?;
+    operation.1.await.map_err(|(code, response)| {
+        if let Ok(value) = serde_json::from_str::<Value>(response.as_str()) {
+            eprintln!("{}", serde_json::to_string_pretty(&value).unwrap())
+        }
+        code
+    })?;
+    Ok(())
 }
 
 
@@ -195,4 +252,4 @@ fn async_operation() -> HcsResult<(HcsOperationHandle, impl Future<Output=Operat
 
     // Sender cannot be dropped (its transmuted), so just strip the outer result
     Ok((handle, async { unsafe { rx.await.unwrap_unchecked() } }))
-}
\ No newline at end of file
+}
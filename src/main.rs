@@ -1,38 +1,177 @@
 use std::path::PathBuf;
+use crate::config::{NetConfig, ShareConfig, VmConfig};
+use crate::control::CtlRequest;
 use crate::hypervisor::Hypervisor;
 
+mod config;
+mod control;
+mod hns;
 mod hypervisor;
+mod monitor;
+mod scratch;
+
+/// Name this runner registers its compute system, pipes, and control socket
+/// under. Only one VM per name can run at a time.
+const VM_NAME: &str = "rust-vm";
 
 /// Uefi Application Test Tool
 #[derive(clap::Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct CLIArgs {
 
-    /// file to boot the vm from
-    efi_file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// file to boot the vm from (overrides the config file's `efi_file`)
+    efi_file: Option<PathBuf>,
+
+    /// path to a TOML config file describing the VM (see vm.example.toml)
+    #[arg(short = 'f', long = "config")]
+    config: Option<PathBuf>,
 
     /// attach a vhd disk to the virtual machine
     #[arg(short, long)]
     disks: Vec<PathBuf>,
 
     /// memory in MB
-    #[arg(short, long, default_value_t = 1024)]
-    memory: u32,
+    #[arg(short, long)]
+    memory: Option<u32>,
 
     /// cpu core count
-    #[arg(short, long, default_value_t = 2)]
-    cores: u8
+    #[arg(short, long)]
+    cores: Option<u8>,
+
+    /// attach a virtual NIC: `nat`, `internal`, or `external=<switch>`, plus
+    /// an optional `,mac=<address>` (repeatable)
+    #[arg(long = "net", value_parser = parse_net)]
+    net: Vec<NetConfig>,
+
+    /// attach an ISO as a CD/DVD drive
+    #[arg(long = "cdrom")]
+    cdrom: Vec<PathBuf>,
+
+    /// boot the first --cdrom entry instead of efi_file when both are given
+    #[arg(long = "boot-cdrom")]
+    boot_cdrom: bool,
+
+    /// create and attach a fresh writable scratch disk of this size in GB
+    #[arg(long = "scratch")]
+    scratch: Vec<u32>,
+
+    /// expose a host directory over SMB: `name=HOSTPATH`, optionally
+    /// followed by `,rw` (default read-only, repeatable)
+    #[arg(long = "share", value_parser = parse_share)]
+    share: Vec<ShareConfig>,
 
 }
 
+/// Parses a `--share` value such as `data=C:\vms\data,rw`.
+fn parse_share(value: &str) -> Result<ShareConfig, String> {
+    let (name, rest) = value.split_once('=')
+        .ok_or("expected name=HOSTPATH[,rw]")?;
+    let mut fields = rest.split(',');
+    let path = PathBuf::from(fields.next().unwrap());
+    let mut read_only = true;
+    for flag in fields {
+        match flag {
+            "rw" => read_only = false,
+            "ro" => read_only = true,
+            other => return Err(format!("unrecognized --share flag '{other}'")),
+        }
+    }
+    Ok(ShareConfig {
+        name: name.to_string(),
+        path,
+        read_only,
+        single_file_mapping: false,
+        restrict_file_access: false,
+    })
+}
+
+/// Parses a `--net` value such as `nat`, `internal,mac=00:15:5d:01:02:03`, or
+/// `external=MySwitch,mac=00:15:5d:01:02:03`.
+fn parse_net(value: &str) -> Result<NetConfig, String> {
+    let mut mode = None;
+    let mut switch = None;
+    let mut mac = None;
+
+    for segment in value.split(',') {
+        if let Some(name) = segment.strip_prefix("external=") {
+            mode = Some("external".to_string());
+            switch = Some(name.to_string());
+        } else if let Some(addr) = segment.strip_prefix("mac=") {
+            mac = Some(addr.to_string());
+        } else if segment == "nat" || segment == "internal" {
+            mode = Some(segment.to_string());
+        } else {
+            return Err(format!("unrecognized --net segment '{segment}'"));
+        }
+    }
+
+    let net = NetConfig {
+        mode: mode.ok_or("--net requires nat, internal, or external=<switch>")?,
+        switch,
+        mac,
+    };
+    net.switch_name()?;
+    Ok(net)
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// pause, resume, save, or stop the currently running VM
+    Ctl {
+        #[command(subcommand)]
+        action: CtlCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CtlCommand {
+    Pause,
+    Resume,
+    /// save the VM's state to a file and stop it
+    Save { path: PathBuf },
+    Shutdown,
+    Terminate,
+}
+
+impl From<CtlCommand> for CtlRequest {
+    fn from(command: CtlCommand) -> Self {
+        match command {
+            CtlCommand::Pause => CtlRequest::Pause,
+            CtlCommand::Resume => CtlRequest::Resume,
+            CtlCommand::Save { path } => CtlRequest::Save { path },
+            CtlCommand::Shutdown => CtlRequest::Shutdown,
+            CtlCommand::Terminate => CtlRequest::Terminate,
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
     let mut args = <CLIArgs as clap::Parser>::parse();
 
-    if !args.efi_file.is_file() {
-        eprintln!("EFI_FILE is not a file!");
-        std::process::exit(1);
+    if let Some(Command::Ctl { action }) = args.command.take() {
+        match control::send_request(VM_NAME, action.into()).await {
+            Ok(control::CtlResponse::Ok) => {}
+            Ok(control::CtlResponse::Err(e)) => {
+                eprintln!("control action failed: {e}");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("failed to reach running VM: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(efi_file) = &args.efi_file {
+        if !efi_file.is_file() {
+            eprintln!("EFI_FILE is not a file!");
+            std::process::exit(1);
+        }
     }
 
     for vhd in &args.disks {
@@ -42,17 +181,65 @@ async fn main() {
         }
     }
 
+    for iso in &args.cdrom {
+        if !iso.is_file() {
+            eprintln!("ISO ({}) is not a file!", iso.display());
+            std::process::exit(1);
+        }
+    }
+
+    for share in &args.share {
+        if !share.path.is_dir() {
+            eprintln!("share '{}' path ({}) is not a directory!", share.name, share.path.display());
+            std::process::exit(1);
+        }
+    }
+
     // canonicalize paths
-    args.efi_file = dunce::canonicalize(args.efi_file).unwrap();
+    args.efi_file = args.efi_file.map(|p| dunce::canonicalize(p).unwrap());
     args.disks = args.disks.into_iter()
         .map(|p|dunce::canonicalize(p).unwrap()).collect();
+    args.cdrom = args.cdrom.into_iter()
+        .map(|p|dunce::canonicalize(p).unwrap()).collect();
+    for share in &mut args.share {
+        share.path = dunce::canonicalize(&share.path).unwrap();
+    }
 
-    let hypervisor = Hypervisor::build("rust-vm", args).await;
-    if let Err(e) = hypervisor {
-        eprintln!("failed to make hypervisor: {:?}", e);
+    let config = match &args.config {
+        Some(path) => match VmConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("failed to read config ({}): {e}", path.display());
+                std::process::exit(1);
+            }
+        },
+        None => VmConfig::default(),
+    };
+    let config = config.overlay_cli(&args);
+    if let Err(e) = config.validate() {
+        eprintln!("invalid VM config: {e}");
         std::process::exit(1);
     }
-    tokio::time::sleep(std::time::Duration::MAX).await;
-}
 
+    let (hypervisor, exit) = match Hypervisor::build(VM_NAME, config).await {
+        Ok(pair) => pair,
+        Err(e) => {
+            eprintln!("failed to make hypervisor: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let status = exit.await;
+    if status.crashed {
+        eprintln!("guest crashed (exit code {})", status.exit_code);
+    }
+
+    // std::process::exit below skips destructors, so ScratchDisk::drop and
+    // Endpoint::drop would never run (and the ctl listener task may be
+    // holding its own Arc clone anyway) - remove the scratch VHDXs and HNS
+    // endpoints explicitly before exiting.
+    hypervisor.cleanup_scratch_disks();
+    hypervisor.cleanup_endpoints();
+    std::process::exit(status.exit_code);
+}
 
@@ -0,0 +1,119 @@
+use std::future::Future;
+use hcs_rs::compute::defs::{HcsCallbackHandle, HcsSystemHandle};
+use hcs_rs::compute::errorcodes::ResultCode;
+use hcs_rs::compute::notifications::HcsNotificationType;
+use hcs_rs::computecore::{register_compute_system_callback, unregister_compute_system_callback};
+use hcs_rs::HcsResult;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+/// The terminal outcome of a watched compute system, surfaced to `main` so
+/// the process can exit with the guest's own code instead of hanging until
+/// killed.
+#[derive(Debug, Clone, Copy)]
+pub struct ExitStatus {
+    pub exit_code: i32,
+    pub crashed: bool,
+}
+
+struct RawEvent {
+    notification_type: u32,
+    data: Value,
+}
+
+/// Owns the HCS system notification callback registration for a running
+/// compute system. Dropping a `Monitor` unregisters the callback.
+#[allow(unused)]
+pub struct Monitor {
+    handle: HcsCallbackHandle,
+}
+
+impl Monitor {
+    /// Registers a notification callback (analogous to `async_operation`'s
+    /// `create_operation` registration) and spawns a task that pretty-prints
+    /// every event, resolving the returned future once the guest exits or
+    /// crashes. This is what the spectrum self-powered-off bug was missing:
+    /// something actually watching the VM's lifecycle instead of sleeping
+    /// forever.
+    pub fn watch(system: HcsSystemHandle) -> HcsResult<(Self, impl Future<Output=ExitStatus>)> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<RawEvent>();
+        let (exit_tx, exit_rx) = oneshot::channel::<ExitStatus>();
+
+        // Leaked once for the lifetime of the registration; the callback
+        // only ever borrows it back, so it survives being invoked many times.
+        let context = Box::into_raw(Box::new(tx)) as *mut winapi::ctypes::c_void;
+        let handle = register_compute_system_callback(system, Some(_callback), context)?;
+
+        tokio::spawn(async move {
+            let mut exit_tx = Some(exit_tx);
+            while let Some(event) = rx.recv().await {
+                if let Ok(pretty) = serde_json::to_string_pretty(&event.data) {
+                    println!("{pretty}");
+                }
+
+                let status = match HcsNotificationType::from(event.notification_type) {
+                    HcsNotificationType::SystemExited => Some(ExitStatus {
+                        exit_code: notification_exit_code(&event.data).unwrap_or(0),
+                        crashed: false,
+                    }),
+                    HcsNotificationType::SystemCrashed => Some(ExitStatus {
+                        exit_code: notification_exit_code(&event.data).unwrap_or(1),
+                        crashed: true,
+                    }),
+                    _ => None,
+                };
+
+                if let Some(status) = status {
+                    if let Some(exit_tx) = exit_tx.take() {
+                        let _ = exit_tx.send(status);
+                    }
+                    break;
+                }
+            }
+        });
+
+        // SAFETY: runs on an OS thread owned by HCS, not a tokio worker, so
+        // it must only push data across the channel: no blocking calls, no
+        // touching the tokio runtime, exactly like the existing `_handler`.
+        unsafe extern "system" fn _callback(
+            notification_type: u32,
+            context: *mut winapi::ctypes::c_void,
+            _status: ResultCode,
+            notification_data: *const u16,
+        ) {
+            let tx = &*(context as *const mpsc::UnboundedSender<RawEvent>);
+            let data = decode_notification_data(notification_data);
+            let _ = tx.send(RawEvent { notification_type, data });
+        }
+
+        Ok((Self { handle }, async move {
+            exit_rx.await.unwrap_or(ExitStatus { exit_code: -1, crashed: true })
+        }))
+    }
+}
+
+/// HCS reports the guest's actual exit/crash code as the notification's
+/// `Result` field (an HRESULT/exit code, not the success/failure of the
+/// notification delivery itself), so pull it out of the JSON we already
+/// pretty-print instead of hardcoding a stand-in value.
+fn notification_exit_code(data: &Value) -> Option<i32> {
+    data.get("Result")?.as_i64().map(|code| code as i32)
+}
+
+unsafe fn decode_notification_data(ptr: *const u16) -> Value {
+    if ptr.is_null() {
+        return Value::Null;
+    }
+    let mut len = 0usize;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    let text = String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len));
+    serde_json::from_str(&text).unwrap_or(Value::Null)
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        let _ = unregister_compute_system_callback(self.handle);
+    }
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use hcs_rs::storage::create_virtual_disk;
+use hcs_rs::HcsResult;
+
+/// A dynamic VHDX created fresh for this run so EFI testing can target
+/// writable storage without the user pre-creating a disk file. Removed again
+/// on drop.
+pub struct ScratchDisk {
+    pub path: PathBuf,
+}
+
+impl ScratchDisk {
+    /// `index` only needs to be unique within this process's scratch disks.
+    pub fn create(size_in_gb: u32, index: usize) -> HcsResult<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "efi-runner-scratch-{}-{index}.vhdx",
+            std::process::id(),
+        ));
+        create_virtual_disk(path.to_string_lossy().as_ref(), size_in_gb as u64 * 1024)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for ScratchDisk {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}